@@ -1,5 +1,5 @@
 use clap::{App,Arg};
-use std::str::FromStr; 
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 use csv::Reader;
 use csv::Writer;
@@ -8,6 +8,11 @@ use b2dp::mechanisms::integerpartition::{IntegerPartitionOptions,integer_partiti
 use b2dp::utilities::weights::WeightTable;
 use b2dp::{exponential_mechanism,ExponentialOptions};
 
+mod optimizer;
+mod output;
+
+use output::{OutputWriter, TrialMetrics};
+
 #[derive(Deserialize,Debug,Clone)]
 struct Record {
     name: String,
@@ -32,7 +37,7 @@ struct BoundRecord {
 enum PartitionStrategies {
     Laplace,
     HistoricalDistance,
-    //HistoricalTrend,
+    HistoricalTrend,
     FromFile,
     Naive,
 }
@@ -43,6 +48,7 @@ impl FromStr for PartitionStrategies {
         match s {
             "Laplace" => Ok(PartitionStrategies::Laplace),
             "HistoricalDistance" => Ok(PartitionStrategies::HistoricalDistance),
+            "HistoricalTrend" => Ok(PartitionStrategies::HistoricalTrend),
             "FromFile" => Ok(PartitionStrategies::FromFile),
             "Naive" => Ok(PartitionStrategies::Naive),
             _ => Err("No matching partition strategy")
@@ -50,6 +56,46 @@ impl FromStr for PartitionStrategies {
     }
 }
 
+/// Project the expected count at release time for each sorted partition
+/// position, by fitting Holt's linear trend method (double exponential
+/// smoothing) over each rank's history across the given dated snapshots
+/// (oldest first) and forecasting one step past the most recent snapshot.
+/// Unlike simple exponential smoothing, which only tracks a lagging level,
+/// this also tracks a per-rank trend term so the projection extrapolates
+/// growth or shrinkage instead of just echoing recent history.
+///
+/// `smoothing` is the smoothing parameter (alpha) in (0, 1], reused for both
+/// the level and trend components: values closer to 1 weight recent
+/// snapshots (and recent changes) more heavily.
+fn project_historical_trend(historical_partitions: &[Vec<i64>], smoothing: f64) -> Vec<i64> {
+    let ranks = historical_partitions.iter().map(|p| p.len()).max().unwrap_or(0);
+    let mut projection: Vec<i64> = Vec::with_capacity(ranks);
+    for rank in 0..ranks {
+        let values: Vec<f64> = historical_partitions
+            .iter()
+            .filter_map(|snapshot| snapshot.get(rank).map(|count| *count as f64))
+            .collect();
+        let forecast = match values.as_slice() {
+            [] => 0.0,
+            [only] => *only,
+            [first, second, rest @ ..] => {
+                let mut level = *second;
+                let mut trend = *second - *first;
+                for &value in rest {
+                    let prev_level = level;
+                    level = smoothing * value + (1.0 - smoothing) * (level + trend);
+                    trend = smoothing * (level - prev_level) + (1.0 - smoothing) * trend;
+                }
+                level + trend
+            }
+        };
+        projection.push(forecast.round() as i64);
+    }
+    projection.sort();
+    projection.reverse();
+    projection
+}
+
 
 fn main() -> Result<(), &'static str> {
     
@@ -62,7 +108,7 @@ fn main() -> Result<(), &'static str> {
             .required(true)
             .index(1))
         .arg(Arg::from("[bounds_strategy] 'The type to use'")
-            .possible_values(&["Laplace", "HistoricalDistance","FromFile","Naive"])
+            .possible_values(&["Laplace", "HistoricalDistance","HistoricalTrend","FromFile","Naive"])
             .short('b')
             .long("bounds")
             .about("Strategy for partition bounds generation.")
@@ -77,11 +123,18 @@ fn main() -> Result<(), &'static str> {
             .takes_value(true)
             )
         .arg(Arg::with_name("HISTORICAL")
-            .about("Sets the file name for historical data")
-            .short('h')    
+            .about("Sets the file name(s) for historical data. For HistoricalTrend, pass one dated CSV per snapshot, oldest first.")
+            .short('h')
             .long("historical")
             .takes_value(true)
+            .multiple(true)
             .required_if("bounds_strategy","HistoricalDistance")
+            .required_if("bounds_strategy","HistoricalTrend")
+        )
+        .arg(Arg::with_name("SMOOTHING")
+            .about("Exponential smoothing parameter (alpha) for the HistoricalTrend bounds strategy.")
+            .long("smoothing")
+            .takes_value(true)
         )
         .arg(Arg::with_name("BOUNDS")
             .about("Sets the input file name for predetermined bounds")
@@ -97,11 +150,31 @@ fn main() -> Result<(), &'static str> {
             .takes_value(true)
         )
         .arg(Arg::with_name("SPARSITY_CONTROL")
-            .about("Whether to use sparsity control.")    
+            .about("Whether to use sparsity control.")
             .short('s')
             .long("sparsity")
             .takes_value(true)
         )
+        .arg(Arg::with_name("OPTIMIZE")
+            .about("Run a SPEA2 Pareto search over partition bound configurations instead of the chosen bounds_strategy, and print the resulting front.")
+            .long("optimize")
+            .takes_value(false)
+        )
+        .arg(Arg::with_name("CONSTANT_TIME")
+            .about("Whether the attribution budget path should use data-independent utility bounds and constant-time sampling.")
+            .long("constant-time")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("OUT")
+            .about("Sets the output file for per-trial AttributedRecords (.csv or .json).")
+            .long("out")
+            .takes_value(true)
+        )
+        .arg(Arg::with_name("METRICS_OUT")
+            .about("Sets the output file for per-trial accuracy metrics (.csv or .json).")
+            .long("metrics-out")
+            .takes_value(true)
+        )
         .get_matches();
 
     let input_file = matches.value_of("INPUT").unwrap();
@@ -115,6 +188,8 @@ fn main() -> Result<(), &'static str> {
 
     let attr_strategy = matches.value_of("attribution_strategy").unwrap_or("Basic");
     println!("Attribution Strategy: {:?}", attr_strategy);
+    let constant_time: bool = matches.value_of_t("CONSTANT_TIME").unwrap_or(true);
+    println!("Constant-time attribution: {:?}", constant_time);
 
     // Preset privacy budgets
     let weight_budget = Eta::new(1,1,1)?;
@@ -139,7 +214,7 @@ fn main() -> Result<(), &'static str> {
     //println!("{:?}", partition);
 
     let total_count: i64 = partition.iter().sum(); // TODO: take total_count as input
-    let total_cells: usize = partition.len(); // TODO: take as argument 
+    let total_cells: usize = partition.len(); // TODO: take as argument
 
     // Read in the bound file
     let bounds_file = matches.value_of("BOUNDS");
@@ -153,17 +228,57 @@ fn main() -> Result<(), &'static str> {
             boundrecords.push(record); 
         }
     }
-    // Read in the Historical file
-    let hist_file = matches.value_of("HISTORICAL");
-    let mut histrecords: Vec<Record> = Vec::new();
-    if hist_file.is_some() {
-        println!("Historical source: {:?}", hist_file.unwrap());
-        let mut histreader = Reader::from_path(hist_file.unwrap()).unwrap();
-        
+    // Read in the Historical file(s). HistoricalDistance uses only the most
+    // recent snapshot; HistoricalTrend uses every snapshot provided, oldest
+    // first, to fit a per-rank trend.
+    let hist_files: Vec<&str> = matches.values_of("HISTORICAL").map(|v| v.collect()).unwrap_or_default();
+    let mut hist_partitions: Vec<Vec<i64>> = Vec::new();
+    for hist_file in hist_files.iter() {
+        println!("Historical source: {:?}", hist_file);
+        let mut histreader = Reader::from_path(hist_file).unwrap();
+        let mut histrecords: Vec<Record> = Vec::new();
         for record in histreader.deserialize() {
-            let record: Record = record.unwrap_or(Record {name: String::from(" "), count: 0}); 
-            histrecords.push(record); 
+            let record: Record = record.unwrap_or(Record {name: String::from(" "), count: 0});
+            histrecords.push(record);
+        }
+        let mut histpartition: Vec<i64> = histrecords.iter().map(|r| r.count as i64 ).collect();
+        histpartition.sort();
+        histpartition.reverse();
+        hist_partitions.push(histpartition);
+    }
+    let smoothing: f64 = matches.value_of_t("SMOOTHING").unwrap_or(0.5);
+
+    // If requested, search for a Pareto front of bound configurations instead
+    // of running the single hard-coded bounds_strategy below. This runs after
+    // the historical file(s) are read so the search can explore reference-based
+    // configurations against the same trend projection `HistoricalTrend` uses.
+    if matches.is_present("OPTIMIZE") {
+        let reference = if hist_partitions.is_empty() {
+            None
+        } else {
+            Some(project_historical_trend(&hist_partitions, smoothing))
+        };
+        let front = optimizer::search(
+            Eta::new(1,1,1)?,
+            total_count as usize,
+            &partition,
+            reference.as_deref(),
+            Default::default(),
+        );
+        println!("Pareto front ({} configurations):", front.len());
+        for individual in front.iter() {
+            println!(
+                "  cells={} sparsity={} slack={} use_reference={} -> budget={:.3} l1_error={:.3} bias={:.3}",
+                individual.encoding.cells,
+                individual.encoding.sparsity,
+                individual.encoding.slack,
+                individual.encoding.use_reference,
+                individual.objectives.0[0],
+                individual.objectives.0[1],
+                individual.objectives.0[2],
+            );
         }
+        return Ok(());
     }
 
     // Get the partition bounds
@@ -181,11 +296,16 @@ fn main() -> Result<(), &'static str> {
                                                              pb_options)?
                      },
         "HistoricalDistance" => {
-            let mut histpartition: Vec<i64> = histrecords.iter().map(|r| r.count as i64 ).collect();
-            histpartition.sort();
-            histpartition.reverse(); 
-            PartitionBound::with_reference( total_count as usize, 
-                                            &histpartition, 
+            let histpartition = hist_partitions.last().cloned().unwrap_or_default();
+            PartitionBound::with_reference( total_count as usize,
+                                            &histpartition,
+                                            &partition,
+                                            ref_budget)?
+         },
+         "HistoricalTrend" => {
+            let trend_projection = project_historical_trend(&hist_partitions, smoothing);
+            PartitionBound::with_reference( total_count as usize,
+                                            &trend_projection,
                                             &partition,
                                             ref_budget)?
          },
@@ -222,53 +342,72 @@ fn main() -> Result<(), &'static str> {
     let bias = weight_table.get_bias(&pb,&partition)?;
     for b in bias.iter() {print!("{:?}, ",b);}
     println!();
+    let estimated_bias: f64 = bias.iter().sum();
 
     // Increase precision of weight_table
     let inc = weight_table.arithmetic_config.precision;
     weight_table.arithmetic_config.increase_precision(inc)?;
+
+    let mut output_writer = OutputWriter::new(matches.value_of("OUT"), matches.value_of("METRICS_OUT"));
+
     // Trial Loop
     for i in 0..num_trials {
         // Get the private partition
         let options: IntegerPartitionOptions = Default::default(); // TODO: Allow option specification
         let ip = integer_partition_mechanism_with_weights(& mut weight_table, &pb, options)?;
 
-        // Reattribute 
+        // Reattribute
         let mut attributed_records = match attr_strategy {
-            "Scoped" => attribute_scoped(attribution_budget, &ip, & mut records, & mut boundrecords, total_count)?,
-            _ => attribute(attribution_budget, &ip, & mut records, total_count)?
+            "Scoped" => attribute_scoped(attribution_budget, &ip, &pb, & mut records, & mut boundrecords, total_count, constant_time)?,
+            _ => attribute(attribution_budget, &ip, &pb, & mut records, total_count, constant_time)?
         };
-        
+
         // Output
         // Sort by canonical ordering:  true count and then name
-        attributed_records.sort_by(|r1, r2| r1.count.cmp(&r2.count).reverse().then(r1.name.cmp(&r2.name)));     
-        
+        attributed_records.sort_by(|r1, r2| r1.count.cmp(&r2.count).reverse().then(r1.name.cmp(&r2.name)));
+
         let counts: Vec<u64> = attributed_records.iter().map(|r| r.count).collect();
-        
+
         //writer.serialize(&counts);
         for b in counts.iter() {print!("{:?}, ",b);}
         println!();
-        
+
         let ideals: Vec<i64> = attributed_records.iter().map(|r| r.ideal_partition).collect();
         for b in ideals.iter() {print!("{:?}, ",b);}
         println!();
         let attr: Vec<i64> = attributed_records.iter().map(|r| r.attributed).collect();
-        
+
         for b in attr.iter() {print!("{:?}, ",b);}
         println!();
+
+        let metrics = TrialMetrics::compute(i, &counts, &ideals, &attr, estimated_bias);
+        output_writer.push_trial(i, &attributed_records, metrics);
      }
 
+    output_writer.finish()?;
+
     Ok(())
 }
 
 
 
+/// A data-independent utility bound derived from the public `PartitionBound`
+/// (`upper`/`count`), rather than from the realized private partition. Using
+/// this instead of `ip.iter().max()` keeps the utility range, and so the
+/// exponential mechanism's sampling loop, independent of private values.
+fn utility_bound(pb: &PartitionBound) -> i64 {
+    *pb.upper.iter().max().unwrap_or(&(pb.count as i64))
+}
+
 /// Scoped reattribution
-fn attribute_scoped(eta: Eta, 
-                    ip: & Vec<i64>,  
-                    records: & mut Vec<Record>, 
-                    boundrecords: & mut Vec<BoundRecord>,  
-                    total_count: i64) 
-    -> Result<Vec<AttributedRecord>, &'static str> 
+fn attribute_scoped(eta: Eta,
+                    ip: & Vec<i64>,
+                    pb: &PartitionBound,
+                    records: & mut Vec<Record>,
+                    boundrecords: & mut Vec<BoundRecord>,
+                    total_count: i64,
+                    constant_time: bool)
+    -> Result<Vec<AttributedRecord>, &'static str>
 {
     // sort the records alphabetically (this ordering is independent of the values of the records.)
     records.sort_by(|r1, r2| r1.name.cmp(&r2.name));
@@ -278,15 +417,16 @@ fn attribute_scoped(eta: Eta,
     // iterate through the records
     for i in 0..records.len() {
         let r = &records[i];
-        let options: ExponentialOptions =  Default::default(); //  TODO:  change this to Use optimized sampling. 
+        let mut options: ExponentialOptions = Default::default();
+        options.optimized_sample = constant_time;
         let rng = GeneratorOpenSSL {};
-        
+
         // Construct the outcome space
         let outcomes: Vec<i64> = (boundrecords[i].lower..boundrecords[i].upper + 1).collect();
-        
+
         let utility_min = 0;
-        let utility_max = *ip.iter().max().unwrap_or(&total_count); // Note: introduces a timing channel
-        
+        let utility_max = if constant_time { utility_bound(pb) } else { *ip.iter().max().unwrap_or(&total_count) };
+
         // construct utility function
         let basic_utility = |x: &i64| (*x - r.count as i64).abs() as f64;
         // select  a value from the integer  partition
@@ -315,18 +455,19 @@ fn attribute_scoped(eta: Eta,
 }
 
 /// Basic reattribution
-fn attribute(eta: Eta, ip: & Vec<i64>,  records: & mut Vec<Record>, total_count: i64) -> Result<Vec<AttributedRecord>, &'static str> 
+fn attribute(eta: Eta, ip: & Vec<i64>, pb: &PartitionBound, records: & mut Vec<Record>, total_count: i64, constant_time: bool) -> Result<Vec<AttributedRecord>, &'static str>
 {
     // sort the records alphabetically (this ordering is independent of the values of the records.)
     records.sort_by(|r1, r2| r1.name.cmp(&r2.name));
     let mut attributed_records: Vec<AttributedRecord> = Vec::new();
     // iterate through the records
     for r in records {
-        let options: ExponentialOptions =  Default::default(); //  TODO:  change this to Use optimized sampling. 
+        let mut options: ExponentialOptions = Default::default();
+        options.optimized_sample = constant_time;
         let rng = GeneratorOpenSSL {};
         let utility_min = 0;
-        let utility_max = *ip.iter().max().unwrap_or(&total_count); // Note: introduces a timing channel
-        
+        let utility_max = if constant_time { utility_bound(pb) } else { *ip.iter().max().unwrap_or(&total_count) };
+
         // construct utility function
         let basic_utility = |x: &i64| (*x - r.count as i64).abs() as f64;
         // select  a value from the integer  partition
@@ -0,0 +1,545 @@
+//! SPEA2 multi-objective search over `PartitionBound` configurations.
+//!
+//! Instead of hard-coding a single `bounds_strategy`, this module explores the
+//! trade-off space between privacy budget consumption, reconstruction error,
+//! and bias, and returns the final Pareto front so a caller can pick a
+//! configuration according to their own accuracy/privacy preference.
+//!
+//! Objectives (1) and (2) are cheap proxies rather than exact quantities: (1)
+//! is the `Eta` numerator fraction actually spent (see `BoundEncoding::
+//! budget_spent`), not the real differential-privacy cost of that `Eta`; (2)
+//! is the distance from each true count to its bound midpoint (see
+//! `evaluate`), not the L1 error of an actually-released partition, since
+//! running `integer_partition_mechanism_with_weights` for every candidate in
+//! every generation would be far too expensive. See the comment inside
+//! `evaluate` for how objective (2) handles bounds narrower than the full partition.
+use b2dp::{Eta, GeneratorOpenSSL};
+use b2dp::utilities::bounds::{PartitionBound, PartitionBoundOptions};
+use b2dp::utilities::weights::WeightTable;
+
+/// An individual's encoding of a candidate `PartitionBound` configuration.
+///
+/// `pb_split`, `sparsity_split` and `ref_split` are the numerator/denominator
+/// splits used to construct the corresponding `Eta` budgets (each component
+/// is chosen independently; they do not sum to a fixed total); `cells` is the number of cells to retain; `sparsity` toggles
+/// sparsity control; `use_reference` toggles constructing the bound from a
+/// historical reference partition (when one is available) instead of noisy
+/// estimates, spending `ref_split` only in that case; `slack` widens or
+/// tightens the per-cell upper/lower bounds before the weight table is built.
+#[derive(Debug, Clone)]
+pub struct BoundEncoding {
+    pub pb_split: (u32, u32, u32),
+    pub sparsity_split: (u32, u32, u32),
+    pub ref_split: (u32, u32, u32),
+    pub cells: usize,
+    pub sparsity: bool,
+    pub use_reference: bool,
+    pub slack: i64,
+}
+
+impl BoundEncoding {
+    fn eta(split: (u32, u32, u32)) -> Result<Eta, &'static str> {
+        Eta::new(split.0, split.1, split.2)
+    }
+
+    /// Build the `PartitionBound` this encoding describes. If `use_reference`
+    /// is set and a historical `reference` partition is available, the bound
+    /// is built from that reference (spending `ref_split`); otherwise it is
+    /// built from noisy estimates over `partition` (spending `pb_split`, and
+    /// `sparsity_split` when sparsity control is on).
+    fn to_bound(&self, total_count: usize, partition: &[i64], reference: Option<&[i64]>) -> Result<PartitionBound, &'static str> {
+        let mut pb = if self.use_reference {
+            match reference {
+                Some(reference) => PartitionBound::with_reference(total_count, reference, partition, Self::eta(self.ref_split)?)?,
+                None => self.bound_from_estimates(total_count, partition)?,
+            }
+        } else {
+            self.bound_from_estimates(total_count, partition)?
+        };
+        if self.slack != 0 {
+            for u in pb.upper.iter_mut() {
+                *u += self.slack;
+            }
+            for l in pb.lower.iter_mut() {
+                *l = (*l - self.slack).max(0);
+            }
+        }
+        Ok(pb)
+    }
+
+    fn bound_from_estimates(&self, total_count: usize, partition: &[i64]) -> Result<PartitionBound, &'static str> {
+        let rng = GeneratorOpenSSL {};
+        let mut pb_options: PartitionBoundOptions = Default::default();
+        if self.sparsity {
+            pb_options.sparsity_control = Some(Self::eta(self.sparsity_split)?);
+        }
+        PartitionBound::from_noisy_estimates(
+            total_count,
+            Some(self.cells.min(partition.len()).max(1)),
+            partition,
+            Self::eta(self.pb_split)?,
+            rng,
+            pb_options,
+        )
+    }
+
+    /// Total privacy budget consumed by this configuration, mirroring exactly
+    /// which split(s) `to_bound` spends on each branch: when `use_reference`
+    /// is on and a reference is available, only `ref_split` is spent (the
+    /// reference branch never calls `bound_from_estimates`); otherwise
+    /// `pb_split` is spent, plus `sparsity_split` when sparsity control is on.
+    fn budget_spent(&self, has_reference: bool) -> f64 {
+        let frac = |s: (u32, u32, u32)| s.0 as f64 / (s.0 + s.1 + s.2).max(1) as f64;
+        if self.use_reference && has_reference {
+            frac(self.ref_split)
+        } else {
+            let mut total = frac(self.pb_split);
+            if self.sparsity {
+                total += frac(self.sparsity_split);
+            }
+            total
+        }
+    }
+}
+
+/// The three objectives SPEA2 minimizes, in order: (1) a proxy for total
+/// privacy budget consumed (the `Eta` numerator fraction spent, not the real
+/// DP cost), (2) a proxy for L1 error between the released and true
+/// partition (distance to the bound midpoint, not an actually-released
+/// partition), and (3) the resulting bias magnitude from `WeightTable::
+/// get_bias`, which is exact. See the module doc comment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Objectives(pub [f64; 3]);
+
+impl Objectives {
+    fn dominates(&self, other: &Objectives) -> bool {
+        let mut strictly_better = false;
+        for i in 0..3 {
+            if self.0[i] > other.0[i] {
+                return false;
+            }
+            if self.0[i] < other.0[i] {
+                strictly_better = true;
+            }
+        }
+        strictly_better
+    }
+
+    fn distance(&self, other: &Objectives) -> f64 {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+/// An individual in the SPEA2 population: an encoding plus its evaluated
+/// objectives.
+#[derive(Debug, Clone)]
+pub struct Individual {
+    pub encoding: BoundEncoding,
+    pub objectives: Objectives,
+}
+
+fn evaluate(
+    encoding: BoundEncoding,
+    weight_budget: Eta,
+    total_count: usize,
+    partition: &[i64],
+    reference: Option<&[i64]>,
+) -> Option<Individual> {
+    let pb = encoding.to_bound(total_count, partition, reference).ok()?;
+    let mut weight_table = WeightTable::from_bounds(weight_budget, &pb, partition).ok()?;
+    let bias = weight_table.get_bias(&pb, partition).ok()?;
+    let bias_magnitude: f64 = bias.iter().map(|b| b.abs()).sum();
+
+    // Expected L1 error is approximated by the released upper/lower slack
+    // around each true count, since running the mechanism for every
+    // candidate in every generation would be far too expensive. `zip` here
+    // intentionally limits the sum to `pb.upper`/`pb.lower`'s length, which
+    // can be shorter than `partition` when `encoding.cells < partition.len()`
+    // (the bound only constrains that many of the largest cells); cells the
+    // bound doesn't cover simply don't contribute to this proxy objective.
+    let l1_error: f64 = partition
+        .iter()
+        .zip(pb.upper.iter().zip(pb.lower.iter()))
+        .map(|(count, (u, l))| {
+            let mid = (*u + *l) as f64 / 2.0;
+            (*count as f64 - mid).abs()
+        })
+        .sum();
+    let budget_spent = encoding.budget_spent(reference.is_some());
+
+    Some(Individual {
+        encoding,
+        objectives: Objectives([budget_spent, l1_error, bias_magnitude]),
+    })
+}
+
+/// Configuration for a single SPEA2 run.
+pub struct Spea2Options {
+    pub population_size: usize,
+    pub archive_size: usize,
+    pub generations: usize,
+}
+
+impl Default for Spea2Options {
+    fn default() -> Self {
+        Spea2Options {
+            population_size: 20,
+            archive_size: 10,
+            generations: 25,
+        }
+    }
+}
+
+/// Run SPEA2 over candidate `PartitionBound` configurations and return the
+/// final Pareto front (the nondominated members of the last population and
+/// the last archive taken together).
+///
+/// `reference` is an optional historical reference partition; when present,
+/// individuals may spend `ref_split` to build their bound from it instead of
+/// from noisy estimates (see `BoundEncoding::to_bound`).
+pub fn search(
+    weight_budget: Eta,
+    total_count: usize,
+    partition: &[i64],
+    reference: Option<&[i64]>,
+    options: Spea2Options,
+) -> Vec<Individual> {
+    let mut rng_state: u64 = 0x9E3779B97F4A7C15;
+    let mut next_u64 = move || {
+        // xorshift64: deterministic and dependency-free, good enough for a
+        // mutation/crossover operator that doesn't need cryptographic rng.
+        rng_state ^= rng_state << 13;
+        rng_state ^= rng_state >> 7;
+        rng_state ^= rng_state << 17;
+        rng_state
+    };
+    let mut next_f64 = || (next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+
+    let random_split = |next_f64: &mut dyn FnMut() -> f64| -> (u32, u32, u32) {
+        (
+            1 + (next_f64() * 9.0) as u32,
+            1 + (next_f64() * 9.0) as u32,
+            1 + (next_f64() * 9.0) as u32,
+        )
+    };
+    let random_encoding = |next_f64: &mut dyn FnMut() -> f64| BoundEncoding {
+        pb_split: random_split(next_f64),
+        sparsity_split: random_split(next_f64),
+        ref_split: random_split(next_f64),
+        cells: (1 + (next_f64() * partition.len() as f64) as usize).max(1),
+        sparsity: next_f64() < 0.5,
+        use_reference: reference.is_some() && next_f64() < 0.5,
+        slack: (next_f64() * 5.0) as i64,
+    };
+
+    let mut population: Vec<Individual> = (0..options.population_size)
+        .filter_map(|_| evaluate(random_encoding(&mut next_f64), weight_budget, total_count, partition, reference))
+        .collect();
+    let mut archive: Vec<Individual> = Vec::new();
+
+    for _ in 0..options.generations {
+        let combined: Vec<Individual> = population.iter().chain(archive.iter()).cloned().collect();
+        let fitness = fitness_assignment(&combined);
+        archive = environmental_selection(combined, &fitness, options.archive_size);
+
+        if archive.is_empty() {
+            // Every candidate in this generation failed to evaluate (or the
+            // archive size is 0); fall back to a fresh random population
+            // rather than indexing into an empty archive.
+            population = (0..options.population_size)
+                .filter_map(|_| evaluate(random_encoding(&mut next_f64), weight_budget, total_count, partition, reference))
+                .collect();
+            continue;
+        }
+        let archive_fitness = fitness_assignment(&archive);
+
+        population = (0..options.population_size)
+            .filter_map(|_| {
+                let p1 = binary_tournament(&archive, &archive_fitness, &mut next_f64)?;
+                let p2 = binary_tournament(&archive, &archive_fitness, &mut next_f64)?;
+                let child = crossover(p1, p2, &mut next_f64);
+                let child = mutate(child, &mut next_f64, partition.len(), reference.is_some());
+                evaluate(child, weight_budget, total_count, partition, reference)
+            })
+            .collect();
+    }
+
+    let combined: Vec<Individual> = population.into_iter().chain(archive.into_iter()).collect();
+    let fitness = fitness_assignment(&combined);
+    combined
+        .into_iter()
+        .zip(fitness.iter())
+        .filter(|(_, f)| **f < 1.0)
+        .map(|(ind, _)| ind)
+        .collect()
+}
+
+/// Raw fitness R(i) + density D(i), per the SPEA2 definition: R(i) is the sum
+/// of strengths of individuals that dominate i (0 for nondominated members),
+/// D(i) = 1 / (sigma_k + 2) where sigma_k is the distance to the k-th nearest
+/// neighbor in objective space, k = floor(sqrt(|P|+|A|)).
+fn fitness_assignment(individuals: &[Individual]) -> Vec<f64> {
+    let n = individuals.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let strength: Vec<usize> = individuals
+        .iter()
+        .map(|i| {
+            individuals
+                .iter()
+                .filter(|j| i.objectives.dominates(&j.objectives))
+                .count()
+        })
+        .collect();
+
+    let raw_fitness: Vec<f64> = (0..n)
+        .map(|i| {
+            individuals
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.objectives.dominates(&individuals[i].objectives))
+                .map(|(j, _)| strength[j] as f64)
+                .sum()
+        })
+        .collect();
+
+    let k = (n as f64).sqrt().floor() as usize;
+    (0..n)
+        .map(|i| {
+            let mut distances: Vec<f64> = individuals
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| individuals[i].objectives.distance(&other.objectives))
+                .collect();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let sigma_k = distances.get(k.saturating_sub(1)).copied().unwrap_or(0.0);
+            raw_fitness[i] + 1.0 / (sigma_k + 2.0)
+        })
+        .collect()
+}
+
+fn environmental_selection(
+    combined: Vec<Individual>,
+    fitness: &[f64],
+    archive_size: usize,
+) -> Vec<Individual> {
+    let mut rated: Vec<(Individual, f64)> = combined.into_iter().zip(fitness.iter().copied()).collect();
+    let mut next_archive: Vec<(Individual, f64)> =
+        rated.iter().filter(|(_, f)| *f < 1.0).cloned().collect();
+
+    if next_archive.len() > archive_size {
+        // Truncate by repeatedly dropping the individual closest to its
+        // nearest neighbor (ties broken by next-nearest distance).
+        while next_archive.len() > archive_size {
+            let objectives: Vec<Objectives> = next_archive.iter().map(|(i, _)| i.objectives).collect();
+            let mut worst_idx = 0;
+            let mut worst_distances: Option<Vec<f64>> = None;
+            for (idx, obj) in objectives.iter().enumerate() {
+                let mut distances: Vec<f64> = objectives
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != idx)
+                    .map(|(_, other)| obj.distance(other))
+                    .collect();
+                distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let is_worse = match &worst_distances {
+                    None => true,
+                    Some(current_worst) => distances < *current_worst,
+                };
+                if is_worse {
+                    worst_idx = idx;
+                    worst_distances = Some(distances);
+                }
+            }
+            next_archive.remove(worst_idx);
+        }
+    } else if next_archive.len() < archive_size {
+        rated.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        for (individual, f) in rated {
+            if next_archive.len() >= archive_size {
+                break;
+            }
+            if f >= 1.0 {
+                next_archive.push((individual, f));
+            }
+        }
+    }
+
+    next_archive.into_iter().map(|(i, _)| i).collect()
+}
+
+/// Select one of two randomly drawn archive members, breaking ties (neither
+/// dominates the other, the common case in multi-objective space) in favor
+/// of the lower (better) SPEA2 fitness. Returns `None` if the archive is
+/// empty, rather than panicking on a `% 0`.
+fn binary_tournament<'a>(archive: &'a [Individual], fitness: &[f64], next_f64: &mut dyn FnMut() -> f64) -> Option<&'a Individual> {
+    if archive.is_empty() {
+        return None;
+    }
+    let i = (next_f64() * archive.len() as f64) as usize % archive.len();
+    let j = (next_f64() * archive.len() as f64) as usize % archive.len();
+    let (a, b) = (&archive[i], &archive[j]);
+    if a.objectives.dominates(&b.objectives) {
+        Some(a)
+    } else if b.objectives.dominates(&a.objectives) {
+        Some(b)
+    } else if fitness[i] <= fitness[j] {
+        Some(a)
+    } else {
+        Some(b)
+    }
+}
+
+fn crossover(p1: &Individual, p2: &Individual, next_f64: &mut dyn FnMut() -> f64) -> BoundEncoding {
+    macro_rules! pick {
+        ($field:ident) => {
+            if next_f64() < 0.5 { p1.encoding.$field } else { p2.encoding.$field }
+        };
+    }
+    BoundEncoding {
+        pb_split: pick!(pb_split),
+        sparsity_split: pick!(sparsity_split),
+        ref_split: pick!(ref_split),
+        cells: pick!(cells),
+        sparsity: pick!(sparsity),
+        use_reference: pick!(use_reference),
+        slack: pick!(slack),
+    }
+}
+
+fn mutate(mut encoding: BoundEncoding, next_f64: &mut dyn FnMut() -> f64, max_cells: usize, reference_available: bool) -> BoundEncoding {
+    let mutate_split = |next_f64: &mut dyn FnMut() -> f64, split: (u32, u32, u32)| -> (u32, u32, u32) {
+        let mut bump = |x: u32| (x as i64 + if next_f64() < 0.5 { 1 } else { -1 }).clamp(1, 9) as u32;
+        (bump(split.0), bump(split.1), bump(split.2))
+    };
+    if next_f64() < 0.1 {
+        encoding.pb_split = mutate_split(next_f64, encoding.pb_split);
+    }
+    if next_f64() < 0.1 {
+        encoding.sparsity_split = mutate_split(next_f64, encoding.sparsity_split);
+    }
+    if next_f64() < 0.1 {
+        encoding.ref_split = mutate_split(next_f64, encoding.ref_split);
+    }
+    if next_f64() < 0.1 {
+        encoding.sparsity = !encoding.sparsity;
+    }
+    if reference_available && next_f64() < 0.1 {
+        encoding.use_reference = !encoding.use_reference;
+    }
+    if next_f64() < 0.1 {
+        let delta = if next_f64() < 0.5 { 1 } else { -1 };
+        encoding.cells = (encoding.cells as i64 + delta).clamp(1, max_cells.max(1) as i64) as usize;
+    }
+    if next_f64() < 0.1 {
+        let delta = if next_f64() < 0.5 { 1 } else { -1 };
+        encoding.slack = (encoding.slack + delta).max(0);
+    }
+    encoding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dummy_encoding() -> BoundEncoding {
+        BoundEncoding {
+            pb_split: (7, 3, 1),
+            sparsity_split: (7, 3, 1),
+            ref_split: (7, 3, 1),
+            cells: 1,
+            sparsity: false,
+            use_reference: false,
+            slack: 0,
+        }
+    }
+
+    fn individual(objectives: [f64; 3]) -> Individual {
+        Individual {
+            encoding: dummy_encoding(),
+            objectives: Objectives(objectives),
+        }
+    }
+
+    #[test]
+    fn dominates_requires_strictly_better_in_at_least_one_objective() {
+        let better = Objectives([1.0, 1.0, 1.0]);
+        let worse = Objectives([2.0, 2.0, 2.0]);
+        assert!(better.dominates(&worse));
+        assert!(!worse.dominates(&better));
+    }
+
+    #[test]
+    fn dominates_is_false_for_mixed_or_equal_objectives() {
+        let equal_a = Objectives([1.0, 1.0, 1.0]);
+        let equal_b = Objectives([1.0, 1.0, 1.0]);
+        assert!(!equal_a.dominates(&equal_b));
+
+        let mixed_a = Objectives([1.0, 2.0, 1.0]);
+        let mixed_b = Objectives([2.0, 1.0, 1.0]);
+        assert!(!mixed_a.dominates(&mixed_b));
+        assert!(!mixed_b.dominates(&mixed_a));
+    }
+
+    #[test]
+    fn fitness_assignment_gives_nondominated_individual_lower_fitness() {
+        let dominator = individual([1.0, 1.0, 1.0]);
+        let dominated = individual([2.0, 2.0, 2.0]);
+        let fitness = fitness_assignment(&[dominator, dominated]);
+
+        // The dominator's raw fitness R is 0 (nothing dominates it), so its
+        // total fitness is just its density term D, which is always < 1.0.
+        // The dominated individual is charged the dominator's strength (1)
+        // as raw fitness, so its total fitness is always >= 1.0.
+        assert!(fitness[0] < 1.0);
+        assert!(fitness[1] >= 1.0);
+    }
+
+    #[test]
+    fn fitness_assignment_of_empty_population_is_empty() {
+        assert!(fitness_assignment(&[]).is_empty());
+    }
+
+    #[test]
+    fn environmental_selection_truncates_by_dropping_the_most_crowded() {
+        // A is a clear outlier; B and C are a close pair (crowded). Exactly
+        // one of the close pair should be dropped to reach archive_size, and
+        // the outlier must always survive truncation.
+        let a = individual([0.0, 10.0, 5.0]);
+        let b = individual([10.0, 0.0, 5.0]);
+        let c = individual([10.1, 0.1, 5.0]);
+        let fitness = vec![0.1, 0.2, 0.3];
+
+        let archive = environmental_selection(vec![a, b, c], &fitness, 2);
+
+        assert_eq!(archive.len(), 2);
+        let has_outlier = archive.iter().any(|ind| ind.objectives.0[1] == 10.0);
+        assert!(has_outlier, "the outlier must survive crowding truncation");
+    }
+
+    #[test]
+    fn environmental_selection_fills_underflow_from_best_dominated() {
+        // Only one individual is nondominated (fitness < 1.0); the archive
+        // should be filled out to archive_size from the remaining dominated
+        // individuals, ordered by fitness.
+        let nondominated = individual([0.0, 0.0, 0.0]);
+        let dominated_a = individual([1.0, 1.0, 1.0]);
+        let dominated_b = individual([2.0, 2.0, 2.0]);
+        let fitness = vec![0.2, 1.5, 2.5];
+
+        let archive = environmental_selection(
+            vec![nondominated, dominated_a, dominated_b],
+            &fitness,
+            3,
+        );
+
+        assert_eq!(archive.len(), 3);
+    }
+}
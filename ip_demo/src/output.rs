@@ -0,0 +1,148 @@
+//! Structured per-trial output: accumulates `AttributedRecord`s and
+//! per-trial accuracy metrics across `NUM_TRIALS` runs, and writes them to a
+//! user-specified file in CSV or JSON depending on the file's extension.
+//!
+//! The JSON path below depends on `serde_json`, which was not a dependency
+//! before this module: this tree ships without a `Cargo.toml`, so there is no
+//! manifest here to add it to; whoever wires up the real manifest for this
+//! crate needs to add a `serde_json` dependency alongside the existing `csv`
+//! and `serde` ones before this will compile.
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use csv::Writer;
+use serde::Serialize;
+
+use crate::AttributedRecord;
+
+/// Per-trial accuracy metrics for one run of
+/// `integer_partition_mechanism_with_weights`.
+#[derive(Serialize, Debug, Clone)]
+pub struct TrialMetrics {
+    pub trial: u32,
+    pub l1_error: f64,
+    pub linf_error: i64,
+    pub sign_flipped_cells: usize,
+    pub realized_bias: f64,
+    pub estimated_bias: f64,
+}
+
+impl TrialMetrics {
+    /// Compute metrics for one trial, comparing the realized attribution
+    /// against the true counts.
+    ///
+    /// `estimated_bias` is the sum of `WeightTable::get_bias`'s per-cell
+    /// estimate, computed once before the trial loop; `realized_bias` is its
+    /// after-the-fact counterpart, so the two are directly comparable.
+    pub fn compute(
+        trial: u32,
+        counts: &[u64],
+        ideal: &[i64],
+        attributed: &[i64],
+        estimated_bias: f64,
+    ) -> TrialMetrics {
+        let l1_error: f64 = counts
+            .iter()
+            .zip(attributed.iter())
+            .map(|(c, a)| (*a - *c as i64).abs() as f64)
+            .sum();
+        let linf_error: i64 = counts
+            .iter()
+            .zip(attributed.iter())
+            .map(|(c, a)| (*a - *c as i64).abs())
+            .max()
+            .unwrap_or(0);
+        let sign_flipped_cells = counts
+            .iter()
+            .zip(ideal.iter().zip(attributed.iter()))
+            .filter(|(count, (ideal, attributed))| {
+                (**ideal - **count as i64).signum() != (**attributed - **count as i64).signum()
+            })
+            .count();
+        let realized_bias: f64 = attributed
+            .iter()
+            .zip(counts.iter())
+            .map(|(a, c)| (*a - *c as i64) as f64)
+            .sum();
+
+        TrialMetrics {
+            trial,
+            l1_error,
+            linf_error,
+            sign_flipped_cells,
+            realized_bias,
+            estimated_bias,
+        }
+    }
+}
+
+/// One attributed record, tagged with the trial it was produced in.
+#[derive(Serialize, Debug, Clone)]
+struct TrialRecord {
+    trial: u32,
+    name: String,
+    count: u64,
+    ideal_partition: i64,
+    attributed: i64,
+}
+
+/// Accumulates records and metrics across trials and writes them out to the
+/// `--out`/`--metrics-out` destinations on `finish`.
+pub struct OutputWriter {
+    out_path: Option<String>,
+    metrics_out_path: Option<String>,
+    records: Vec<TrialRecord>,
+    metrics: Vec<TrialMetrics>,
+}
+
+impl OutputWriter {
+    pub fn new(out_path: Option<&str>, metrics_out_path: Option<&str>) -> OutputWriter {
+        OutputWriter {
+            out_path: out_path.map(String::from),
+            metrics_out_path: metrics_out_path.map(String::from),
+            records: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    pub fn push_trial(&mut self, trial: u32, attributed_records: &[AttributedRecord], metrics: TrialMetrics) {
+        for r in attributed_records {
+            self.records.push(TrialRecord {
+                trial,
+                name: r.name.clone(),
+                count: r.count,
+                ideal_partition: r.ideal_partition,
+                attributed: r.attributed,
+            });
+        }
+        self.metrics.push(metrics);
+    }
+
+    pub fn finish(&self) -> Result<(), &'static str> {
+        if let Some(path) = &self.out_path {
+            write_serialized(path, &self.records)?;
+        }
+        if let Some(path) = &self.metrics_out_path {
+            write_serialized(path, &self.metrics)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_serialized<T: Serialize>(path: &str, rows: &[T]) -> Result<(), &'static str> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") => {
+            let mut file = File::create(path).map_err(|_| "Could not create output file")?;
+            let json = serde_json::to_string_pretty(rows).map_err(|_| "Could not serialize to JSON")?;
+            file.write_all(json.as_bytes()).map_err(|_| "Could not write output file")?;
+        }
+        _ => {
+            let mut writer = Writer::from_path(path).map_err(|_| "Could not create output file")?;
+            for row in rows {
+                writer.serialize(row).map_err(|_| "Could not write output row")?;
+            }
+            writer.flush().map_err(|_| "Could not flush output file")?;
+        }
+    }
+    Ok(())
+}